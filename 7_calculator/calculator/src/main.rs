@@ -1,25 +1,39 @@
 use pest::Parser;
 use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest_derive::Parser;
+use std::collections::HashMap;
 use std::io::{self, BufRead};
 use lazy_static::lazy_static;
+use thiserror::Error;
 
 #[derive(Parser)]
 #[grammar_inline = r#"
 WHITESPACE = _{ " " }
 
-integer = @{ ASCII_DIGIT+ }
+number = @{ ASCII_DIGIT+ ~ ("." ~ ASCII_DIGIT+)? }
+hex_literal = @{ "0x" ~ ASCII_HEX_DIGIT+ }
+bin_literal = @{ "0b" ~ ASCII_BIN_DIGIT+ }
+oct_literal = @{ "0o" ~ ASCII_OCT_DIGIT+ }
+identifier = @{ ASCII_ALPHA ~ (ASCII_ALPHANUMERIC | "_")* }
+
+function_name = { "sin" | "cos" | "exp" | "ln" | "sqrt" | "abs" }
+call = { function_name ~ "(" ~ expr ~ ")" }
 
 add = { "+" }
 subtract = { "-" }
 multiply = { "*" }
 divide = { "/" }
 modulo = { "%" }
+power = { "^" }
 unary_minus = { "-" }
+factorial = { "!" }
+assign = { "=" }
 
-bin_op = _{ add | subtract | multiply | divide | modulo }
+bin_op = _{ assign | add | subtract | multiply | divide | modulo | power }
+postfix_op = _{ factorial }
 
-atom = _{ unary_minus? ~ (integer | "(" ~ expr ~ ")") }
+primary = _{ call | hex_literal | bin_literal | oct_literal | number | identifier | "(" ~ expr ~ ")" }
+atom = _{ unary_minus? ~ primary ~ postfix_op* }
 
 expr = { atom ~ (bin_op ~ atom)* }
 
@@ -29,13 +43,23 @@ struct CalculatorParser;
 
 #[derive(Debug)]
 pub enum Expr {
-    Integer(i32),
+    Number(f64),
     UnaryMinus(Box<Expr>),
     BinOp {
         lhs: Box<Expr>,
         op: OpType,
         rhs: Box<Expr>,
     },
+    Call {
+        name: String,
+        arg: Box<Expr>,
+    },
+    Factorial(Box<Expr>),
+    Variable(String),
+    Assign {
+        name: String,
+        value: Box<Expr>,
+    },
 }
 
 #[derive(Debug)]
@@ -45,74 +69,316 @@ pub enum OpType {
     Multiply,
     Divide,
     Modulo,
+    Power,
+}
+
+#[derive(Debug, Error)]
+pub enum CalcError {
+    #[error("division by zero")]
+    DivideByZero,
+    #[error("modulo by zero")]
+    ModuloByZero,
+    #[error("result is out of range")]
+    Overflow,
+    #[error("factorial is only defined for non-negative integers, got {0}")]
+    InvalidFactorial(f64),
+    #[error("unknown function: {0}")]
+    UnknownFunction(String),
+    #[error("unbound variable: {0}")]
+    UnboundVariable(String),
+    #[error("unknown base: {0} (must be between 2 and 36)")]
+    UnknownBase(u32),
+    #[error("left-hand side of assignment must be a variable")]
+    InvalidAssignmentTarget,
+    #[error("integer literal out of range: {0}")]
+    InvalidLiteral(String),
 }
 
 lazy_static! {
     static ref PRATT_PARSER: PrattParser<Rule> = {
         PrattParser::new()
             // Define precedence from lowest to highest
+            .op(Op::infix(Rule::assign, Assoc::Right))
             .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::subtract, Assoc::Left))
             .op(Op::infix(Rule::multiply, Assoc::Left) | Op::infix(Rule::divide, Assoc::Left) | Op::infix(Rule::modulo, Assoc::Left))
             .op(Op::prefix(Rule::unary_minus))
+            .op(Op::infix(Rule::power, Assoc::Right))
+            .op(Op::postfix(Rule::factorial))
     };
 }
 
-pub fn parse_expr(pairs: pest::iterators::Pairs<Rule>) -> Expr {
+pub fn parse_expr(pairs: pest::iterators::Pairs<Rule>) -> Result<Expr, CalcError> {
     PRATT_PARSER
         .map_primary(|primary| match primary.as_rule() {
-            Rule::integer => Expr::Integer(primary.as_str().parse::<i32>().unwrap()),
+            Rule::number => Ok(Expr::Number(primary.as_str().parse::<f64>().unwrap())),
+            Rule::hex_literal => i64::from_str_radix(&primary.as_str()[2..], 16)
+                .map(|v| Expr::Number(v as f64))
+                .map_err(|_| CalcError::InvalidLiteral(primary.as_str().to_string())),
+            Rule::bin_literal => i64::from_str_radix(&primary.as_str()[2..], 2)
+                .map(|v| Expr::Number(v as f64))
+                .map_err(|_| CalcError::InvalidLiteral(primary.as_str().to_string())),
+            Rule::oct_literal => i64::from_str_radix(&primary.as_str()[2..], 8)
+                .map(|v| Expr::Number(v as f64))
+                .map_err(|_| CalcError::InvalidLiteral(primary.as_str().to_string())),
+            Rule::call => {
+                let mut inner = primary.into_inner();
+                let name = inner.next().unwrap().as_str().to_string();
+                let arg = parse_expr(inner.next().unwrap().into_inner())?;
+                Ok(Expr::Call {
+                    name,
+                    arg: Box::new(arg),
+                })
+            }
+            Rule::identifier => Ok(Expr::Variable(primary.as_str().to_string())),
             Rule::expr => parse_expr(primary.into_inner()),
             rule => unreachable!("Expr::parse expected atom, found {:?}", rule),
         })
         .map_infix(|lhs, op, rhs| {
+            let lhs = lhs?;
+            let rhs = rhs?;
+            if op.as_rule() == Rule::assign {
+                let name = match lhs {
+                    Expr::Variable(name) => name,
+                    _ => return Err(CalcError::InvalidAssignmentTarget),
+                };
+                return Ok(Expr::Assign {
+                    name,
+                    value: Box::new(rhs),
+                });
+            }
             let op = match op.as_rule() {
                 Rule::add => OpType::Add,
                 Rule::subtract => OpType::Subtract,
                 Rule::multiply => OpType::Multiply,
                 Rule::divide => OpType::Divide,
                 Rule::modulo => OpType::Modulo,
+                Rule::power => OpType::Power,
                 rule => unreachable!("Expr::parse expected infix operation, found {:?}", rule),
             };
-            Expr::BinOp {
+            Ok(Expr::BinOp {
                 lhs: Box::new(lhs),
                 op,
                 rhs: Box::new(rhs),
-            }
+            })
         })
         .map_prefix(|op, rhs| match op.as_rule() {
-            Rule::unary_minus => Expr::UnaryMinus(Box::new(rhs)),
+            Rule::unary_minus => Ok(Expr::UnaryMinus(Box::new(rhs?))),
+            _ => unreachable!(),
+        })
+        .map_postfix(|lhs, op| match op.as_rule() {
+            Rule::factorial => Ok(Expr::Factorial(Box::new(lhs?))),
             _ => unreachable!(),
         })
         .parse(pairs)
 }
 
 impl Expr {
-    pub fn evaluate(&self) -> i32 {
+    pub fn evaluate(&self, env: &mut HashMap<String, f64>) -> Result<f64, CalcError> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::UnaryMinus(expr) => Ok(-expr.evaluate(env)?),
+            Expr::BinOp { lhs, op, rhs } => {
+                let left = lhs.evaluate(env)?;
+                let right = rhs.evaluate(env)?;
+                match op {
+                    OpType::Add => {
+                        let result = left + right;
+                        if result.is_infinite() {
+                            Err(CalcError::Overflow)
+                        } else {
+                            Ok(result)
+                        }
+                    }
+                    OpType::Subtract => {
+                        let result = left - right;
+                        if result.is_infinite() {
+                            Err(CalcError::Overflow)
+                        } else {
+                            Ok(result)
+                        }
+                    }
+                    OpType::Multiply => {
+                        let result = left * right;
+                        if result.is_infinite() {
+                            Err(CalcError::Overflow)
+                        } else {
+                            Ok(result)
+                        }
+                    }
+                    OpType::Divide => {
+                        if right == 0.0 {
+                            Err(CalcError::DivideByZero)
+                        } else {
+                            Ok(left / right)
+                        }
+                    }
+                    OpType::Modulo => {
+                        if right == 0.0 {
+                            Err(CalcError::ModuloByZero)
+                        } else {
+                            Ok(left % right)
+                        }
+                    }
+                    OpType::Power => {
+                        let result = left.powf(right);
+                        if result.is_infinite() {
+                            Err(CalcError::Overflow)
+                        } else {
+                            Ok(result)
+                        }
+                    }
+                }
+            }
+            Expr::Call { name, arg } => {
+                let value = arg.evaluate(env)?;
+                match name.as_str() {
+                    "sin" => Ok(value.sin()),
+                    "cos" => Ok(value.cos()),
+                    "exp" => Ok(value.exp()),
+                    "ln" => Ok(value.ln()),
+                    "sqrt" => Ok(value.sqrt()),
+                    "abs" => Ok(value.abs()),
+                    _ => Err(CalcError::UnknownFunction(name.clone())),
+                }
+            }
+            Expr::Factorial(expr) => {
+                let value = expr.evaluate(env)?;
+                if value < 0.0 || value.fract() != 0.0 {
+                    return Err(CalcError::InvalidFactorial(value));
+                }
+                let result = (1..=value as u64).fold(1.0, |acc, n| acc * n as f64);
+                if result.is_infinite() {
+                    Err(CalcError::Overflow)
+                } else {
+                    Ok(result)
+                }
+            }
+            Expr::Variable(name) => env
+                .get(name)
+                .copied()
+                .ok_or_else(|| CalcError::UnboundVariable(name.clone())),
+            Expr::Assign { name, value } => {
+                let value = value.evaluate(env)?;
+                env.insert(name.clone(), value);
+                Ok(value)
+            }
+        }
+    }
+
+    /// Folds the tree into a reusable closure over the free variable `x`, so a
+    /// parsed expression can be sampled repeatedly without retraversing `Expr`.
+    pub fn compile(&self) -> Box<dyn Fn(f64) -> f64> {
         match self {
-            Expr::Integer(value) => *value,
-            Expr::UnaryMinus(expr) => -expr.evaluate(),
+            Expr::Number(value) => {
+                let value = *value;
+                Box::new(move |_x| value)
+            }
+            Expr::Variable(name) => {
+                assert_eq!(name, "x", "compile only supports the free variable 'x'");
+                Box::new(|x| x)
+            }
+            Expr::UnaryMinus(expr) => {
+                let inner = expr.compile();
+                Box::new(move |x| -inner(x))
+            }
             Expr::BinOp { lhs, op, rhs } => {
-                let left = lhs.evaluate();
-                let right = rhs.evaluate();
+                let l = lhs.compile();
+                let r = rhs.compile();
                 match op {
-                    OpType::Add => left + right,
-                    OpType::Subtract => left - right,
-                    OpType::Multiply => left * right,
-                    OpType::Divide => left / right,
-                    OpType::Modulo => left % right,
+                    OpType::Add => Box::new(move |x| l(x) + r(x)),
+                    OpType::Subtract => Box::new(move |x| l(x) - r(x)),
+                    OpType::Multiply => Box::new(move |x| l(x) * r(x)),
+                    OpType::Divide => Box::new(move |x| l(x) / r(x)),
+                    OpType::Modulo => Box::new(move |x| l(x) % r(x)),
+                    OpType::Power => Box::new(move |x| l(x).powf(r(x))),
                 }
             }
+            Expr::Call { name, arg } => {
+                let inner = arg.compile();
+                let name = name.clone();
+                Box::new(move |x| {
+                    let value = inner(x);
+                    match name.as_str() {
+                        "sin" => value.sin(),
+                        "cos" => value.cos(),
+                        "exp" => value.exp(),
+                        "ln" => value.ln(),
+                        "sqrt" => value.sqrt(),
+                        "abs" => value.abs(),
+                        _ => f64::NAN,
+                    }
+                })
+            }
+            Expr::Factorial(expr) => {
+                let inner = expr.compile();
+                Box::new(move |x| {
+                    let value = inner(x);
+                    if value < 0.0 || value.fract() != 0.0 {
+                        return f64::NAN;
+                    }
+                    (1..=value as u64).fold(1.0, |acc, n| acc * n as f64)
+                })
+            }
+            Expr::Assign { value, .. } => value.compile(),
         }
     }
 }
 
+/// Formats `value` as a signed integer in `base` (2..=36), e.g. for hex/binary output.
+fn format_radix(value: i64, base: u32) -> Result<String, CalcError> {
+    if !(2..=36).contains(&base) {
+        return Err(CalcError::UnknownBase(base));
+    }
+    if value == 0 {
+        return Ok("0".to_string());
+    }
+    let negative = value < 0;
+    let mut magnitude = value.unsigned_abs();
+    let mut digits = Vec::new();
+    while magnitude > 0 {
+        let digit = (magnitude % base as u64) as u32;
+        digits.push(std::char::from_digit(digit, base).unwrap());
+        magnitude /= base as u64;
+    }
+    if negative {
+        digits.push('-');
+    }
+    Ok(digits.into_iter().rev().collect())
+}
+
 fn main() -> io::Result<()> {
     let stdin = io::stdin();
+    let mut env: HashMap<String, f64> = HashMap::new();
+    let mut output_base: u32 = 10;
     for line in stdin.lock().lines() {
-        match CalculatorParser::parse(Rule::equation, &line?) {
+        let line = line?;
+        if let Some(rest) = line.strip_prefix(":base ") {
+            match rest.trim().parse::<u32>() {
+                Ok(base) => match format_radix(0, base) {
+                    Ok(_) => {
+                        output_base = base;
+                        println!("Output base set to {}", base);
+                    }
+                    Err(e) => eprintln!("Error: {}", e),
+                },
+                Err(_) => eprintln!("Error: expected an integer base"),
+            }
+            continue;
+        }
+        match CalculatorParser::parse(Rule::equation, &line) {
             Ok(mut pairs) => {
-                let expr = parse_expr(pairs.next().unwrap().into_inner());
-                println!("Result: {}", expr.evaluate());
+                match parse_expr(pairs.next().unwrap().into_inner())
+                    .and_then(|expr| expr.evaluate(&mut env))
+                {
+                    Ok(value) if output_base != 10 && value.is_finite() && value.fract() == 0.0 => {
+                        match format_radix(value as i64, output_base) {
+                            Ok(formatted) => println!("Result: {}", formatted),
+                            Err(e) => eprintln!("Error: {}", e),
+                        }
+                    }
+                    Ok(value) => println!("Result: {}", value),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
             }
             Err(e) => {
                 eprintln!("Parse failed: {:?}", e);